@@ -3,7 +3,11 @@
 //! Rust implementation of [pigpio's I2C Sniffer](https://github.com/joan2937/pigpio/tree/master/EXAMPLES/C/I2C_SNIFFER).
 
 use std::fmt;
-extern crate tokio_core;
+#[macro_use]
+extern crate futures;
+
+mod stream;
+pub use stream::I2cMessageStream;
 
 /// Structure for parsing I2C Messages from raw SDA and SCL inputs
 #[derive(Debug)]
@@ -14,19 +18,128 @@ pub struct I2cEngine {
     current_bit: u8,
     active: bool,
     bytes: Vec<I2cByte>,
+    segments: Vec<I2cSegment>,
+    // Timing state, only populated when samples are fed via `update_i2c_at`
+    first_bit_tick: Option<u32>,
+    scl_low_since: Option<u32>,
+    avg_low_period_us: Option<f64>,
+    stretches: Vec<ClockStretchEvent>,
+    scl_filter: LineFilter,
+    sda_filter: LineFilter,
+}
+
+/// A digital noise filter for a single GPIO line, modeled on the analog/
+/// digital spike suppressor found on hardware I2C peripherals (e.g. the
+/// STM32 `anfoff` bit). A new logical level is only accepted once it has
+/// been observed for `depth` consecutive raw samples, suppressing pulses
+/// narrower than that.
+#[derive(Debug, Clone)]
+struct LineFilter {
+    depth: u8,
+    stable: bool,
+    candidate: bool,
+    run: u8,
+}
+
+impl LineFilter {
+    /// A filter with `depth` of 0 or 1 passes every sample through
+    /// unchanged
+    fn new(depth: u8) -> LineFilter {
+        LineFilter {
+            depth,
+            stable: true,
+            candidate: true,
+            run: 0,
+        }
+    }
+
+    /// Feed one raw sample, returning the filtered (debounced) level
+    fn sample(&mut self, level: bool) -> bool {
+        if self.depth <= 1 {
+            self.stable = level;
+            return self.stable;
+        }
+
+        if level == self.candidate {
+            if self.run < self.depth {
+                self.run += 1;
+            }
+        } else {
+            self.candidate = level;
+            self.run = 1;
+        }
+
+        if self.run >= self.depth {
+            self.stable = self.candidate;
+        }
+
+        self.stable
+    }
 }
 
-/// Structure containing a complete I2C message comprised of `I2cByte`s
+/// Structure containing a complete I2C message, from START to STOP
+///
+/// A message is made up of one or more `I2cSegment`s. There is more than one
+/// segment only when a repeated START was seen before the terminating STOP,
+/// splitting the transaction into separate address/payload frames (the usual
+/// write-then-read register access pattern).
 #[derive(Debug, PartialEq)]
 pub struct I2cMessage {
-    pub message: Vec<I2cByte>,
+    pub segments: Vec<I2cSegment>,
 }
 
+/// A single START (or repeated-START) to the next repeated-START or STOP,
+/// comprised of `I2cByte`s
+#[derive(Debug, PartialEq, Clone)]
+pub struct I2cSegment {
+    pub bytes: Vec<I2cByte>,
+    /// Clock-stretching events observed while this segment was captured, if
+    /// the engine was fed timestamped samples via `update_i2c_at`
+    pub stretches: Vec<ClockStretchEvent>,
+}
+
+/// A clock-stretching event: SCL was held low for far longer than the
+/// running average SCL low-phase duration, indicating a slave stalled the
+/// bus. The baseline is the low phase alone, not the full bit period.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ClockStretchEvent {
+    /// The pigpio `tick` (microseconds) at which SCL was released back high
+    pub tick_us: u32,
+    /// How long SCL was held low, in microseconds
+    pub duration_us: u32,
+}
+
+/// The address and direction decoded from the leading byte(s) of an `I2cMessage`
+///
+/// Both 7-bit and 10-bit addressing are represented here; `ten_bit` tells you
+/// which form `bits` should be interpreted as.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct I2cAddress {
+    /// The address itself: 7 bits wide for standard addressing, 10 bits wide
+    /// for extended addressing
+    pub bits: u16,
+    /// `true` if this address was sent using 10-bit addressing
+    pub ten_bit: bool,
+    /// `true` if this is a read request, `false` if a write request
+    pub read: bool,
+}
+
+/// The high five bits of the first address byte that mark a 10-bit address,
+/// per the reserved `0b11110xx` pattern used by the STM32 I2C peripheral
+const TEN_BIT_ADDR_MASK: u8 = 0b1111_1000;
+const TEN_BIT_ADDR_PATTERN: u8 = 0b1111_0000;
+
 /// A single byte of I2C Data, including ACK or NAK state
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct I2cByte {
     pub data: u8,
     pub status: I2cStatus,
+    /// The pigpio `tick` (microseconds) of this byte's first data bit, if
+    /// the engine was fed timestamped samples via `update_i2c_at`
+    pub first_bit_tick: Option<u32>,
+    /// The pigpio `tick` (microseconds) of this byte's ACK/NAK bit, if the
+    /// engine was fed timestamped samples via `update_i2c_at`
+    pub ack_tick: Option<u32>,
 }
 
 /// Current behavior of the SCL line
@@ -46,29 +159,128 @@ enum SdaState {
 }
 
 impl I2cMessage {
-    /// Obtain only the bytes from an I2C Message, discarding ACK and NAKs
+    /// Convenience accessor for the address frame of this message's first
+    /// segment, the common case where no repeated START occurred. See
+    /// `I2cSegment::address` for callers that need to handle every segment.
+    pub fn address(&self) -> Option<I2cAddress> {
+        self.segments.first().and_then(I2cSegment::address)
+    }
+
+    /// Convenience accessor for the payload of this message's first segment,
+    /// the common case where no repeated START occurred. See
+    /// `I2cSegment::get_payload` for callers that need to handle every segment.
+    pub fn get_payload(&self) -> Vec<u8> {
+        self.segments.first().map(I2cSegment::get_payload).unwrap_or_default()
+    }
+}
+
+impl I2cSegment {
+    /// Decode the address frame that begins this segment, if present
+    ///
+    /// The first latched byte after a START (or repeated START) is always an
+    /// address/direction frame. If its high five bits match the `0b11110xx`
+    /// pattern reserved for 10-bit addressing, the two low address bits are
+    /// combined with the full second byte to form the 10-bit address.
+    /// Otherwise, the frame is decoded as a standard 7-bit address.
+    pub fn address(&self) -> Option<I2cAddress> {
+        let first = self.bytes.get(0)?;
+
+        if (first.data & TEN_BIT_ADDR_MASK) == TEN_BIT_ADDR_PATTERN {
+            let second = self.bytes.get(1)?;
+            let hi_bits = (first.data & 0b0000_0110) >> 1;
+            Some(I2cAddress {
+                bits: ((hi_bits as u16) << 8) | second.data as u16,
+                ten_bit: true,
+                read: (first.data & 1) != 0,
+            })
+        } else {
+            Some(I2cAddress {
+                bits: (first.data >> 1) as u16,
+                ten_bit: false,
+                read: (first.data & 1) != 0,
+            })
+        }
+    }
+
+    /// Number of leading bytes that make up the address frame, even if the
+    /// frame is truncated (e.g. a STOP arrives right after a 10-bit prefix
+    /// byte, before the second address byte was ever captured)
+    ///
+    /// This checks the prefix pattern directly rather than going through
+    /// `address()`, which returns `None` for a truncated 10-bit frame and
+    /// would otherwise make a dangling prefix byte look like payload data.
+    fn address_frame_len(&self) -> usize {
+        match self.bytes.get(0) {
+            None => 0,
+            Some(first) if (first.data & TEN_BIT_ADDR_MASK) == TEN_BIT_ADDR_PATTERN => {
+                if self.bytes.len() >= 2 { 2 } else { 1 }
+            }
+            Some(_) => 1,
+        }
+    }
+
+    /// Obtain only the data bytes from this segment, discarding the address
+    /// frame as well as ACK and NAK status
     pub fn get_payload(&self) -> Vec<u8> {
         let mut out: Vec<u8> = Vec::new();
-        for b in &self.message {
+        for b in self.bytes.iter().skip(self.address_frame_len()) {
             out.push(b.data);
         }
         out
     }
+
+    /// Average observed SCL period across this segment's bytes, in
+    /// microseconds, if timing information was recorded
+    pub fn bit_period_us(&self) -> Option<f64> {
+        let mut total = 0f64;
+        let mut count = 0u32;
+
+        for b in &self.bytes {
+            if let (Some(first), Some(ack)) = (b.first_bit_tick, b.ack_tick) {
+                total += ack.wrapping_sub(first) as f64 / 8.0;
+                count += 1;
+            }
+        }
+
+        if count == 0 {
+            None
+        } else {
+            Some(total / count as f64)
+        }
+    }
+
+    /// Effective I2C bus clock frequency, in Hz, derived from `bit_period_us`
+    pub fn bus_frequency_hz(&self) -> Option<f64> {
+        self.bit_period_us().and_then(|period_us| {
+            if period_us > 0.0 {
+                Some(1_000_000.0 / period_us)
+            } else {
+                None
+            }
+        })
+    }
 }
 
 impl fmt::Display for I2cMessage {
     /// Implementation of the display trait for use with `println!()`, etc.
+    ///
+    /// Segments split by a repeated START are separated by `|`.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut out = String::new();
-        out.push_str(&("["));
-        for byte in &self.message {
-            out.push_str(&(format!("{:02X}", byte.data)));
-            out.push_str(&(format!("{}", match byte.status {
-                I2cStatus::Ack => "+",
-                I2cStatus::Nak => "-",
-            })));
-        }
-        out.push_str(&(format!("]")));
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i > 0 {
+                out.push_str(&("|"));
+            }
+            out.push_str(&("["));
+            for byte in &segment.bytes {
+                out.push_str(&(format!("{:02X}", byte.data)));
+                out.push_str(&(format!("{}", match byte.status {
+                    I2cStatus::Ack => "+",
+                    I2cStatus::Nak => "-",
+                })));
+            }
+            out.push_str(&(format!("]")));
+        }
         write!(f, "{}", out)
     }
 }
@@ -83,6 +295,26 @@ pub enum DecodeState {
     Idle,
     Pending,
     Complete(I2cMessage),
+    /// The captured edges don't describe a valid I2C transaction: the
+    /// in-progress message was abandoned, or (for `UnexpectedEdge`) a stray
+    /// edge was seen while idle
+    Error(I2cAbortReason),
+}
+
+/// Why an in-progress message was abandoned, modeled on the abort reasons
+/// reported by hardware I2C controllers (e.g. embassy/rp2040)
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum I2cAbortReason {
+    /// The address frame, or a data byte of a write segment, was NAKed
+    NoAcknowledge,
+    /// A STOP or repeated START arrived before a full byte (8 data bits plus
+    /// ACK/NAK) was latched
+    IncompleteByte { bits_received: u8 },
+    /// An edge occurred that doesn't fit any valid I2C transition: SDA rose
+    /// while SCL was steady high (the shape of a STOP) with no START seen
+    /// first. Other invalid edges, such as SDA and SCL changing in the same
+    /// sample, are not detected.
+    UnexpectedEdge,
 }
 
 /// Representation of ACK/NAK bit after every 8 bits of data
@@ -92,6 +324,15 @@ pub enum I2cStatus {
     Nak
 }
 
+/// Factor by which an SCL low phase must exceed the running average SCL
+/// low-phase duration (not the full bit period) before it is flagged as a
+/// clock-stretching event
+const CLOCK_STRETCH_THRESHOLD: f64 = 3.0;
+
+/// Weight given to each new low-phase sample when updating the running
+/// average low-phase duration (simple exponential moving average)
+const CLOCK_PERIOD_EMA_ALPHA: f64 = 0.25;
+
 impl I2cEngine {
     /// Create a new I2CEngine in the idle and empty state
     pub fn new() -> I2cEngine {
@@ -102,14 +343,54 @@ impl I2cEngine {
             current_bit: 0u8,
             active: false,
             bytes: Vec::new(),
+            segments: Vec::new(),
+            first_bit_tick: None,
+            scl_low_since: None,
+            avg_low_period_us: None,
+            stretches: Vec::new(),
+            scl_filter: LineFilter::new(1),
+            sda_filter: LineFilter::new(1),
         }
     }
 
+    /// Configure a digital noise filter on both SDA and SCL: a level is only
+    /// accepted once it has been observed stable for `samples` consecutive
+    /// raw samples, suppressing shorter glitches before they reach the edge
+    /// detector. With the default (no filter applied), every sample is
+    /// trusted as-is.
+    pub fn with_filter(mut self, samples: u8) -> I2cEngine {
+        self.scl_filter = LineFilter::new(samples);
+        self.sda_filter = LineFilter::new(samples);
+        self
+    }
+
     /// Process one sample of SDA and SCL data from an I2C bus.
     ///
     /// Returns the current state, as well as a message if a STOP condition was
     ///   just received
     pub fn update_i2c(&mut self, new_scl: bool, new_sda: bool) -> DecodeState {
+        self.update(new_scl, new_sda, None)
+    }
+
+    /// Process one sample of SDA and SCL data from an I2C bus, tagged with
+    /// the pigpio `tick` (microseconds) it was captured at.
+    ///
+    /// This is identical to `update_i2c`, but additionally records the tick
+    /// of each byte's first data bit and ACK/NAK bit, and watches for
+    /// clock-stretching (an SCL low phase far longer than the running
+    /// average SCL low-phase duration). Use `I2cSegment::bit_period_us` and
+    /// `I2cSegment::bus_frequency_hz` on a completed message to recover the
+    /// observed bus timing.
+    pub fn update_i2c_at(&mut self, new_scl: bool, new_sda: bool, tick_us: u32) -> DecodeState {
+        self.update(new_scl, new_sda, Some(tick_us))
+    }
+
+    fn update(&mut self, raw_scl: bool, raw_sda: bool, tick_us: Option<u32>) -> DecodeState {
+        // Run the raw samples through the configured noise filter before
+        // they ever reach edge detection
+        let new_scl = self.scl_filter.sample(raw_scl);
+        let new_sda = self.sda_filter.sample(raw_sda);
+
         // Determine current SCL and SDA behavior
         let scl_state = match (self.old_scl, new_scl) {
             (false, false) => SclState::Steady,
@@ -129,23 +410,83 @@ impl I2cEngine {
         self.old_scl = new_scl;
         self.old_sda = new_sda;
 
+        if let Some(tick) = tick_us {
+            self.track_clock(&scl_state, tick);
+        }
+
         // Process state transition, based on current data
         match (scl_state, sda_state, self.active, new_scl, self.current_bit) {
             (SclState::Steady, SdaState::Rising, true, true, _) => {
                 // Stop condition, after previously receiving a Start Condition
-                let ret = I2cMessage{message:self.bytes.to_owned()};
-                self.bytes.clear();
-                self.partial_data = 0;
-                self.current_bit = 0;
-                self.active = false;
+                //
+                // Note: the SCL rising edge that necessarily precedes any
+                // STOP is itself indistinguishable from a bit-capture edge
+                // until this very sample confirms it as a STOP, so
+                // `current_bit` always carries one unavoidable phantom bit
+                // for an otherwise-complete byte boundary; only excess
+                // beyond that counts as a truncated byte
+                let bits_received = self.current_bit.saturating_sub(1);
+                if bits_received != 0 {
+                    self.reset_transaction();
+                    return DecodeState::Error(I2cAbortReason::IncompleteByte{bits_received});
+                }
+
+                self.segments.push(I2cSegment{
+                    bytes: self.bytes.to_owned(),
+                    stretches: self.stretches.to_owned(),
+                });
+
+                if self.last_segment_naked() {
+                    self.reset_transaction();
+                    return DecodeState::Error(I2cAbortReason::NoAcknowledge);
+                }
+
+                let ret = I2cMessage{segments: self.segments.to_owned()};
+                self.reset_transaction();
                 return DecodeState::Complete(ret);
             },
             (SclState::Steady, SdaState::Falling, false, true, _) => {
                 // Start condition from idle state
                 self.active = true;
             },
+            (SclState::Steady, SdaState::Falling, true, true, _) => {
+                // Repeated start while already active: close out the bytes
+                // accumulated so far as a segment, and keep accumulating a
+                // fresh one without returning to Idle. See the comment on
+                // the STOP arm above for why `current_bit` is offset by one.
+                let bits_received = self.current_bit.saturating_sub(1);
+                if bits_received != 0 {
+                    self.reset_transaction();
+                    return DecodeState::Error(I2cAbortReason::IncompleteByte{bits_received});
+                }
+
+                self.segments.push(I2cSegment{
+                    bytes: self.bytes.to_owned(),
+                    stretches: self.stretches.to_owned(),
+                });
+
+                if self.last_segment_naked() {
+                    self.reset_transaction();
+                    return DecodeState::Error(I2cAbortReason::NoAcknowledge);
+                }
+
+                self.bytes.clear();
+                self.stretches.clear();
+                self.partial_data = 0;
+                self.current_bit = 0;
+            },
+            (SclState::Steady, SdaState::Rising, false, true, _) => {
+                // SDA released high while SCL is steady high, with no
+                // preceding Start Condition: electrically this is what a
+                // STOP looks like, but a STOP can't be valid without a
+                // Start first (most likely the capture began mid-transaction)
+                return DecodeState::Error(I2cAbortReason::UnexpectedEdge);
+            },
             (SclState::Rising, _, true, _, 0...7) => {
                 // Capture bit of whole byte
+                if self.current_bit == 0 {
+                    self.first_bit_tick = tick_us;
+                }
                 self.partial_data <<= 1;
                 self.partial_data |= if new_sda {1} else {0};
                 self.current_bit += 1;
@@ -154,10 +495,13 @@ impl I2cEngine {
                 // 8 bits received, observe ACK/NAK and record byte
                 self.bytes.push(I2cByte{
                     data: self.partial_data,
-                    status: if new_sda {I2cStatus::Nak} else {I2cStatus::Ack}
+                    status: if new_sda {I2cStatus::Nak} else {I2cStatus::Ack},
+                    first_bit_tick: self.first_bit_tick,
+                    ack_tick: tick_us,
                 });
                 self.partial_data = 0;
                 self.current_bit = 0;
+                self.first_bit_tick = None;
             },
             _ => {},
         }
@@ -168,11 +512,76 @@ impl I2cEngine {
             false => DecodeState::Idle
         }
     }
+
+    /// `true` if the most recently closed segment had its address frame
+    /// NAKed (no slave acknowledged that address), or is a write segment
+    /// with a NAKed data byte (the slave rejected the data)
+    ///
+    /// For a 10-bit address both frame bytes are checked, since either one
+    /// can be NAKed by a non-matching slave; for a 7-bit address only the
+    /// single address byte exists to check. Data bytes of a read segment
+    /// are not checked: a master ends a read by deliberately NAKing the
+    /// final data byte, so treating that NAK as an error would misreport
+    /// normal termination as a bus fault.
+    fn last_segment_naked(&self) -> bool {
+        self.segments.last().map_or(false, |seg| {
+            let addr_len = seg.address_frame_len();
+            let is_write = seg.address().map_or(false, |a| !a.read);
+            seg.bytes
+                .iter()
+                .enumerate()
+                .any(|(i, b)| b.status == I2cStatus::Nak && (i < addr_len || is_write))
+        })
+    }
+
+    /// Abandon the in-progress transaction and return to the idle state,
+    /// discarding any accumulated bytes, segments and timing data
+    fn reset_transaction(&mut self) {
+        self.bytes.clear();
+        self.segments.clear();
+        self.stretches.clear();
+        self.partial_data = 0;
+        self.current_bit = 0;
+        self.active = false;
+        self.first_bit_tick = None;
+        self.scl_low_since = None;
+    }
+
+    /// Update the running average of SCL low-phase durations, flagging any
+    /// low phase that runs far longer than that average as a
+    /// clock-stretching event
+    fn track_clock(&mut self, scl_state: &SclState, tick_us: u32) {
+        match scl_state {
+            SclState::Falling => {
+                self.scl_low_since = Some(tick_us);
+            },
+            SclState::Rising => {
+                if let Some(low_since) = self.scl_low_since.take() {
+                    let duration_us = tick_us.wrapping_sub(low_since);
+
+                    if let Some(avg) = self.avg_low_period_us {
+                        if self.active && (duration_us as f64) > avg * CLOCK_STRETCH_THRESHOLD {
+                            self.stretches.push(ClockStretchEvent{
+                                tick_us,
+                                duration_us,
+                            });
+                        }
+                        self.avg_low_period_us = Some(
+                            avg + CLOCK_PERIOD_EMA_ALPHA * (duration_us as f64 - avg)
+                        );
+                    } else {
+                        self.avg_low_period_us = Some(duration_us as f64);
+                    }
+                }
+            },
+            SclState::Steady => {},
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{I2cEngine, DecodeState, I2cMessage};
+    use super::{I2cEngine, DecodeState, I2cMessage, I2cAddress, I2cAbortReason};
 
     /// Helper function to send a START condition
     fn start(machine: &mut I2cEngine)
@@ -181,6 +590,15 @@ mod test {
         assert_eq!(machine.update_i2c(true, false), DecodeState::Pending);
     }
 
+    /// Helper function to send a repeated START condition mid-transaction:
+    ///   SDA is released high, SCL rises, then SDA falls while SCL is high
+    fn repeated_start(machine: &mut I2cEngine)
+    {
+        assert_eq!(machine.update_i2c(false, true), DecodeState::Pending);
+        assert_eq!(machine.update_i2c(true, true), DecodeState::Pending);
+        assert_eq!(machine.update_i2c(true, false), DecodeState::Pending);
+    }
+
     /// Helper function to send one bit of data
     fn feed_one_bit(machine: &mut I2cEngine, bit: bool)
     {
@@ -189,8 +607,14 @@ mod test {
         assert_eq!(machine.update_i2c(false, bit), DecodeState::Pending);
     }
 
-    /// Helper function to send 8 bits of data and an ACK
+    /// Helper function to send 8 bits of data, always followed by an ACK
     fn feed_one_byte(machine: &mut I2cEngine, byte: u8)
+    {
+        feed_one_byte_with_ack(machine, byte, false)
+    }
+
+    /// Helper function to send 8 bits of data followed by an ACK or NAK
+    fn feed_one_byte_with_ack(machine: &mut I2cEngine, byte: u8, nak: bool)
     {
         let mut byte = byte;
 
@@ -201,9 +625,9 @@ mod test {
             feed_one_bit(machine, state)
         }
 
-        // Always Ack
-        assert_eq!(machine.update_i2c(true, false), DecodeState::Pending);
-        assert_eq!(machine.update_i2c(false, false), DecodeState::Pending);
+        // Ack/Nak
+        assert_eq!(machine.update_i2c(true, nak), DecodeState::Pending);
+        assert_eq!(machine.update_i2c(false, nak), DecodeState::Pending);
     }
 
     /// Helper function to send a STOP condition
@@ -218,7 +642,8 @@ mod test {
     }
 
     /// Test various sequences of bytes to be processed by the engine. Assert
-    ///   that message is reassembled correctly
+    ///   that message is reassembled correctly, with the leading address
+    ///   frame excluded from the payload
     #[test]
     fn test_bytes() {
         let tests = vec!(
@@ -233,6 +658,9 @@ mod test {
 
         for t in tests {
             start(&mut x);
+
+            // Every message begins with an address/direction frame
+            feed_one_byte(&mut x, 0xAA);
             for b in &t {
                 feed_one_byte(&mut x, *b);
             }
@@ -240,4 +668,272 @@ mod test {
             assert_eq!(stop(&mut x).get_payload(), t);
         }
     }
+
+    /// Test decoding of a standard 7-bit address/direction frame
+    #[test]
+    fn test_address_7_bit() {
+        let mut x = I2cEngine::new();
+
+        start(&mut x);
+        feed_one_byte(&mut x, 0b1010_0001); // Address 0x50, read
+        feed_one_byte(&mut x, 0x55);
+        let msg = stop(&mut x);
+
+        assert_eq!(msg.address(), Some(I2cAddress{bits: 0x50, ten_bit: false, read: true}));
+        assert_eq!(msg.get_payload(), vec!(0x55));
+    }
+
+    /// Test decoding of a 10-bit address/direction frame, using the reserved
+    ///   `0b11110xx` leading pattern
+    #[test]
+    fn test_address_10_bit() {
+        let mut x = I2cEngine::new();
+
+        start(&mut x);
+        feed_one_byte(&mut x, 0b1111_0101); // Ten-bit prefix, high addr bits 0b10, read
+        feed_one_byte(&mut x, 0xAB);
+        let msg = stop(&mut x);
+
+        assert_eq!(msg.address(), Some(I2cAddress{bits: 0x2AB, ten_bit: true, read: true}));
+        assert_eq!(msg.get_payload(), Vec::<u8>::new());
+    }
+
+    /// A STOP right after a 10-bit prefix byte, before the second address
+    ///   byte ever arrives, must not let the dangling prefix byte leak out
+    ///   as if it were payload data
+    #[test]
+    fn test_address_10_bit_truncated_not_emitted_as_payload() {
+        let mut x = I2cEngine::new();
+
+        start(&mut x);
+        feed_one_byte(&mut x, 0b1111_0101); // Ten-bit prefix, ACKed, then STOP
+        let msg = stop(&mut x);
+
+        assert_eq!(msg.address(), None);
+        assert_eq!(msg.get_payload(), Vec::<u8>::new());
+    }
+
+    /// An empty message (no bytes latched between START and STOP) has no
+    ///   address frame to decode
+    #[test]
+    fn test_address_empty_message() {
+        let mut x = I2cEngine::new();
+
+        start(&mut x);
+        let msg = stop(&mut x);
+
+        assert_eq!(msg.address(), None);
+    }
+
+    /// A repeated START mid-transaction should split the message into two
+    ///   segments, each with its own address frame and payload, rather than
+    ///   merging the bytes of both into one
+    #[test]
+    fn test_repeated_start() {
+        let mut x = I2cEngine::new();
+
+        start(&mut x);
+        feed_one_byte(&mut x, 0b1010_0000); // Address 0x50, write
+        feed_one_byte(&mut x, 0x10); // Register to read from
+
+        repeated_start(&mut x);
+        feed_one_byte(&mut x, 0b1010_0001); // Address 0x50, read
+        feed_one_byte(&mut x, 0x55); // Register contents
+
+        let msg = stop(&mut x);
+
+        assert_eq!(msg.segments.len(), 2);
+
+        assert_eq!(msg.segments[0].address(), Some(I2cAddress{bits: 0x50, ten_bit: false, read: false}));
+        assert_eq!(msg.segments[0].get_payload(), vec!(0x10));
+
+        assert_eq!(msg.segments[1].address(), Some(I2cAddress{bits: 0x50, ten_bit: false, read: true}));
+        assert_eq!(msg.segments[1].get_payload(), vec!(0x55));
+    }
+
+    /// Feeding timestamped samples via `update_i2c_at` should record each
+    ///   byte's first-bit and ACK tick, and let the segment report the
+    ///   observed bit period and bus frequency
+    #[test]
+    fn test_timed_byte_records_ticks_and_period() {
+        let mut x = I2cEngine::new();
+        let mut tick = 0u32;
+
+        assert_eq!(x.update_i2c_at(true, true, tick), DecodeState::Idle);
+        tick += 10;
+        assert_eq!(x.update_i2c_at(true, false, tick), DecodeState::Pending); // START
+        tick += 10;
+
+        let mut shifted = 0b1010_0001u8;
+        let mut first_bit_tick = None;
+
+        for bit_idx in 0..8 {
+            let bit = 0x80 == (shifted & 0x80);
+            shifted <<= 1;
+
+            assert_eq!(x.update_i2c_at(false, bit, tick), DecodeState::Pending);
+            tick += 10;
+            assert_eq!(x.update_i2c_at(true, bit, tick), DecodeState::Pending);
+            if bit_idx == 0 {
+                first_bit_tick = Some(tick);
+            }
+            tick += 10;
+            assert_eq!(x.update_i2c_at(false, bit, tick), DecodeState::Pending);
+            tick += 10;
+        }
+
+        assert_eq!(x.update_i2c_at(true, false, tick), DecodeState::Pending); // ACK
+        let ack_tick = tick;
+        tick += 10;
+        assert_eq!(x.update_i2c_at(false, false, tick), DecodeState::Pending);
+        tick += 10;
+
+        // STOP
+        assert_eq!(x.update_i2c_at(false, false, tick), DecodeState::Pending);
+        tick += 10;
+        assert_eq!(x.update_i2c_at(true, false, tick), DecodeState::Pending);
+        tick += 10;
+        let msg = match x.update_i2c_at(true, true, tick) {
+            DecodeState::Complete(m) => m,
+            _ => panic!("Unexpected incomplete message!"),
+        };
+
+        let byte0 = &msg.segments[0].bytes[0];
+        assert_eq!(byte0.first_bit_tick, first_bit_tick);
+        assert_eq!(byte0.ack_tick, Some(ack_tick));
+
+        let expected_period = (ack_tick - first_bit_tick.unwrap()) as f64 / 8.0;
+        assert_eq!(msg.segments[0].bit_period_us(), Some(expected_period));
+        assert_eq!(msg.segments[0].bus_frequency_hz(), Some(1_000_000.0 / expected_period));
+    }
+
+    /// An SCL low phase far longer than the established running average
+    ///   should be flagged as a clock-stretching event
+    #[test]
+    fn test_clock_stretch_detected() {
+        let mut x = I2cEngine::new();
+        let mut tick = 0u32;
+
+        assert_eq!(x.update_i2c_at(true, true, tick), DecodeState::Idle);
+        tick += 10;
+        assert_eq!(x.update_i2c_at(true, false, tick), DecodeState::Pending); // START
+        tick += 10;
+
+        // A handful of normal bits establish a ~10us low-phase average
+        for _ in 0..16 {
+            assert_eq!(x.update_i2c_at(false, false, tick), DecodeState::Pending);
+            tick += 10;
+            assert_eq!(x.update_i2c_at(true, false, tick), DecodeState::Pending);
+            tick += 10;
+        }
+
+        // A slave stretches the clock: SCL held low far longer than usual
+        assert_eq!(x.update_i2c_at(false, false, tick), DecodeState::Pending);
+        tick += 500;
+        assert_eq!(x.update_i2c_at(true, false, tick), DecodeState::Pending);
+        tick += 10;
+
+        // STOP
+        assert_eq!(x.update_i2c_at(false, false, tick), DecodeState::Pending);
+        tick += 10;
+        assert_eq!(x.update_i2c_at(true, false, tick), DecodeState::Pending);
+        tick += 10;
+        let msg = match x.update_i2c_at(true, true, tick) {
+            DecodeState::Complete(m) => m,
+            _ => panic!("Unexpected incomplete message!"),
+        };
+
+        assert_eq!(msg.segments[0].stretches.len(), 1);
+        assert_eq!(msg.segments[0].stretches[0].duration_us, 500);
+    }
+
+    /// A single-sample glitch on SDA should be suppressed once a noise
+    ///   filter is configured, instead of being mistaken for a START
+    #[test]
+    fn test_filter_suppresses_short_glitch() {
+        let mut x = I2cEngine::new().with_filter(3);
+
+        // Establish a stable idle bus (SCL high, SDA high)
+        assert_eq!(x.update_i2c(true, true), DecodeState::Idle);
+
+        // A single-sample dip on SDA is filtered out and never reaches the
+        // edge detector
+        assert_eq!(x.update_i2c(true, false), DecodeState::Idle);
+        assert_eq!(x.update_i2c(true, true), DecodeState::Idle);
+    }
+
+    /// Without a filter configured, the same single-sample dip is trusted
+    ///   as a genuine START condition
+    #[test]
+    fn test_without_filter_glitch_is_seen() {
+        let mut x = I2cEngine::new();
+
+        assert_eq!(x.update_i2c(true, true), DecodeState::Idle);
+        assert_eq!(x.update_i2c(true, false), DecodeState::Pending);
+    }
+
+    /// A STOP arriving with fewer than 8 data bits latched should abandon
+    ///   the message and report how many bits were actually received
+    #[test]
+    fn test_incomplete_byte_on_stop() {
+        let mut x = I2cEngine::new();
+        start(&mut x);
+
+        // Only 2 of 8 data bits latched before STOP arrives
+        feed_one_bit(&mut x, true);
+        feed_one_bit(&mut x, false);
+
+        assert_eq!(x.update_i2c(false, false), DecodeState::Pending);
+        assert_eq!(x.update_i2c(true, false), DecodeState::Pending);
+        assert_eq!(
+            x.update_i2c(true, true),
+            DecodeState::Error(I2cAbortReason::IncompleteByte{bits_received: 2})
+        );
+    }
+
+    /// A NAKed address frame should be reported as a protocol error rather
+    ///   than silently returned as a normal message
+    #[test]
+    fn test_nak_on_address_reports_error() {
+        let mut x = I2cEngine::new();
+        start(&mut x);
+
+        // No slave acknowledges this address
+        feed_one_byte_with_ack(&mut x, 0b1010_0001, true);
+
+        assert_eq!(x.update_i2c(false, false), DecodeState::Pending);
+        assert_eq!(x.update_i2c(true, false), DecodeState::Pending);
+        assert_eq!(x.update_i2c(true, true), DecodeState::Error(I2cAbortReason::NoAcknowledge));
+    }
+
+    /// A NAK on the second byte of a 10-bit address frame must be reported
+    ///   too, not just a NAK on the leading prefix byte
+    #[test]
+    fn test_nak_on_second_byte_of_ten_bit_address_reports_error() {
+        let mut x = I2cEngine::new();
+        start(&mut x);
+
+        // Prefix byte is ACKed (a slave matches the high address bits), but
+        // no slave matches the full 10-bit address in the second byte
+        feed_one_byte(&mut x, 0b1111_0101);
+        feed_one_byte_with_ack(&mut x, 0xAB, true);
+
+        assert_eq!(x.update_i2c(false, false), DecodeState::Pending);
+        assert_eq!(x.update_i2c(true, false), DecodeState::Pending);
+        assert_eq!(x.update_i2c(true, true), DecodeState::Error(I2cAbortReason::NoAcknowledge));
+    }
+
+    /// An SDA release that looks exactly like a STOP, but with no Start
+    ///   Condition ever having been seen, doesn't fit any valid transition
+    #[test]
+    fn test_unexpected_edge_without_start() {
+        let mut x = I2cEngine::new();
+
+        assert_eq!(x.update_i2c(false, false), DecodeState::Idle);
+        assert_eq!(x.update_i2c(true, false), DecodeState::Idle);
+        assert_eq!(
+            x.update_i2c(true, true),
+            DecodeState::Error(I2cAbortReason::UnexpectedEdge)
+        );
+    }
 }
@@ -0,0 +1,114 @@
+//! Async `Stream` adapter over a raw sample source
+//!
+//! This lets callers compose the decoder into an async pipeline (logging,
+//! filtering, forwarding to sockets, ...) instead of hand-rolling a blocking
+//! read loop around `I2cEngine::update_i2c`. This type only knows about
+//! samples and `I2cMessage`s - it is generic over any `futures::Stream` of
+//! `(scl, sda)` samples, not tied to pigpio or any particular transport.
+//!
+//! Nothing in this crate currently produces such a stream from a real
+//! pigpio source: `pigpio_i2c::pigpio::PigpioReader` is a synchronous
+//! `std::io::Read`-based iterator (see the `pigpio_i2c` binary crate), not
+//! an `AsyncRead`/`tokio_core` source. Wiring pigpio's notification stream
+//! up to this adapter asynchronously is still future work.
+
+use futures::{Async, Poll, Stream};
+
+use super::{DecodeState, I2cAbortReason, I2cEngine, I2cMessage};
+
+/// Adapts any `Stream` of raw `(scl, sda)` samples into a `Stream` of
+/// decoded `I2cMessage`s, feeding each sample into an internal `I2cEngine`
+/// and yielding on either a completed message or a protocol error, so a
+/// malformed frame is surfaced to the consumer instead of silently dropped.
+pub struct I2cMessageStream<S> {
+    inner: S,
+    engine: I2cEngine,
+}
+
+impl<S> I2cMessageStream<S> {
+    /// Wrap a sample stream, decoding with a fresh `I2cEngine`
+    pub fn new(inner: S) -> I2cMessageStream<S> {
+        I2cMessageStream {
+            inner,
+            engine: I2cEngine::new(),
+        }
+    }
+
+    /// Wrap a sample stream, decoding with an already-configured engine
+    /// (for example, one built with `I2cEngine::with_filter`)
+    pub fn with_engine(inner: S, engine: I2cEngine) -> I2cMessageStream<S> {
+        I2cMessageStream { inner, engine }
+    }
+}
+
+impl<S> Stream for I2cMessageStream<S>
+where
+    S: Stream<Item = (bool, bool)>,
+{
+    type Item = Result<I2cMessage, I2cAbortReason>;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, S::Error> {
+        loop {
+            let sample = match try_ready!(self.inner.poll()) {
+                Some(sample) => sample,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            match self.engine.update_i2c(sample.0, sample.1) {
+                DecodeState::Complete(msg) => return Ok(Async::Ready(Some(Ok(msg)))),
+                DecodeState::Error(reason) => return Ok(Async::Ready(Some(Err(reason)))),
+                DecodeState::Idle | DecodeState::Pending => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::stream::iter_ok;
+    use futures::Stream;
+
+    use super::I2cMessageStream;
+    use super::super::{I2cAbortReason, I2cMessage, I2cSegment};
+
+    /// A START immediately followed by a STOP, with no bytes in between
+    fn empty_message_samples() -> Vec<(bool, bool)> {
+        vec![
+            (true, true),
+            (true, false), // START
+            (false, false),
+            (true, false),
+            (true, true), // STOP
+        ]
+    }
+
+    /// A completed message should be yielded as `Some(Ok(..))`
+    #[test]
+    fn test_yields_complete_message() {
+        let inner = iter_ok::<_, ()>(empty_message_samples());
+        let stream = I2cMessageStream::new(inner);
+
+        let results: Vec<_> = stream.wait().collect();
+        assert_eq!(
+            results,
+            vec![Ok(Ok(I2cMessage {
+                segments: vec![I2cSegment { bytes: Vec::new(), stretches: Vec::new() }],
+            }))]
+        );
+    }
+
+    /// A protocol error mid-stream should be yielded as `Some(Err(..))`
+    /// rather than silently dropped
+    #[test]
+    fn test_yields_protocol_error() {
+        // SDA released high while SCL is steady high, with no preceding
+        // Start Condition: looks exactly like a STOP, but isn't valid
+        let samples = vec![(false, false), (true, false), (true, true)];
+        let inner = iter_ok::<_, ()>(samples);
+        let stream = I2cMessageStream::new(inner);
+
+        let results: Vec<_> = stream.wait().collect();
+        assert_eq!(results, vec![Ok(Err(I2cAbortReason::UnexpectedEdge))]);
+    }
+}